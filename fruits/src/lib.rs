@@ -9,9 +9,10 @@ use curve::Curve;
 use difficulty_object::DifficultyObject;
 use movement::Movement;
 
-use parse::{Beatmap, HitObjectKind, Mods, PathType};
+use parse::{Beatmap, GameMode, HitObjectKind, Mods, PathType};
 use std::cmp::Ordering;
 use std::convert::identity;
+use std::fmt;
 
 const SECTION_LENGTH: f32 = 750.0;
 const STAR_SCALING_FACTOR: f32 = 0.153;
@@ -26,21 +27,41 @@ macro_rules! binary_search {
 }
 
 /// Star calculation for osu!ctb maps
+pub fn stars(map: &Beatmap, mods: impl Mods) -> Result<DifficultyAttributes, CatchError> {
+    stars_partial(map, mods, None)
+}
+
+/// Like [`stars`] but only considers the first `passed_objects` catch objects
+/// (fruits and droplets in time order, not tiny droplets), i.e. computes
+/// difficulty for a failed or still in-progress play.
+///
+/// Passing `None` is equivalent to [`stars`].
+///
+/// Returns [`CatchError::WrongMode`] if `map` is neither an osu!catch map
+/// nor an osu!standard map (which converts directly into osu!catch).
 // Slider parsing based on https://github.com/osufx/catch-the-pp
-pub fn stars(map: &Beatmap, mods: impl Mods) -> DifficultyAttributes {
+pub fn stars_partial(
+    map: &Beatmap,
+    mods: impl Mods,
+    passed_objects: Option<usize>,
+) -> Result<DifficultyAttributes, CatchError> {
+    if map.mode != GameMode::Catch && map.mode != GameMode::Osu {
+        return Err(CatchError::WrongMode(map.mode));
+    }
+
     if map.hit_objects.len() < 2 {
-        return DifficultyAttributes::default();
+        return Ok(DifficultyAttributes::default());
     }
 
     let attributes = map.attributes().mods(mods);
     let with_hr = mods.hr();
     let mut ticks = Vec::new(); // using the same buffer for all sliders
 
-    let mut fruits = 0;
-    let mut droplets = 0;
+    // Times of every tiny droplet in the map; only counted for the passed
+    // portion further down once `passed_objects` truncation has happened.
+    let mut tiny_droplet_times = Vec::new();
 
-    // BUG: Incorrect object order on 2B maps that have fruits within sliders
-    let mut hit_objects = map
+    let hit_objects = map
         .hit_objects
         .iter()
         .scan((None, 0.0), |(last_pos, last_time), h| match &h.kind {
@@ -51,9 +72,7 @@ pub fn stars(map: &Beatmap, mods: impl Mods) -> DifficultyAttributes {
                     h = h.with_hr(last_pos, last_time);
                 }
 
-                fruits += 1;
-
-                Some(Some(FruitOrJuice::Fruit(Some(h))))
+                Some(Some(FruitOrJuice::Fruit(Some((h, true)))))
             }
             HitObjectKind::Slider {
                 pixel_len,
@@ -137,12 +156,17 @@ pub fn stars(map: &Beatmap, mods: impl Mods) -> DifficultyAttributes {
                 }
 
                 let mut slider_objects = Vec::with_capacity(repeats * (ticks.len() + 1));
-                slider_objects.push((h.pos, h.start_time));
+                slider_objects.push((h.pos, h.start_time, true)); // head fruit
 
                 if *repeats <= 1 {
-                    slider_objects.append(&mut ticks); // automatically empties buffer for next slider
+                    slider_objects.extend(ticks.drain(..).map(|(pos, time)| (pos, time, false)));
                 } else {
-                    slider_objects.append(&mut ticks.clone());
+                    slider_objects.extend(
+                        ticks
+                            .iter()
+                            .cloned()
+                            .map(|(pos, time)| (pos, time, false)),
+                    );
 
                     for repeat_id in 1..*repeats - 1 {
                         let dist = (repeat_id % 2) as f32 * *pixel_len;
@@ -150,10 +174,12 @@ pub fn stars(map: &Beatmap, mods: impl Mods) -> DifficultyAttributes {
                         let pos = curve.point_at_distance(dist);
 
                         // Reverse tick / last legacy tick
-                        slider_objects.push((pos, h.start_time + time_offset));
+                        slider_objects.push((pos, h.start_time + time_offset, true));
 
                         ticks.reverse();
-                        slider_objects.extend_from_slice(&ticks); // tick time doesn't need to be adjusted for some reason
+                        slider_objects.extend(
+                            ticks.iter().cloned().map(|(pos, time)| (pos, time, false)),
+                        ); // tick time doesn't need to be adjusted for some reason
                     }
 
                     // Handling last span separatly so that `ticks` vector isn't cloned again
@@ -161,21 +187,47 @@ pub fn stars(map: &Beatmap, mods: impl Mods) -> DifficultyAttributes {
                     let time_offset = (duration / *repeats as f32) * (*repeats - 1) as f32;
                     let pos = curve.point_at_distance(dist);
 
-                    slider_objects.push((pos, h.start_time + time_offset));
+                    slider_objects.push((pos, h.start_time + time_offset, true));
 
                     ticks.reverse();
-                    slider_objects.append(&mut ticks); // automatically empties buffer for next slider
+                    slider_objects.extend(ticks.drain(..).map(|(pos, time)| (pos, time, false)));
                 }
 
                 // Slider tail
                 let dist_end = (*repeats % 2) as f32 * *pixel_len;
                 let pos = curve.point_at_distance(dist_end);
-                slider_objects.push((pos, h.start_time + duration));
+                slider_objects.push((pos, h.start_time + duration, true)); // tail fruit
+
+                // Reverse sliders push their ticks in playback order, not time
+                // order, so sort by time before pairing up neighbours below.
+                slider_objects
+                    .sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+
+                // Tiny droplets are generated between every pair of consecutive
+                // nested objects (head, ticks, reverse fruits, tail) that are more
+                // than 80ms apart, halving the gap until it is at most 100ms.
+                for window in slider_objects.windows(2) {
+                    let since_last_tick = window[1].1 - window[0].1;
 
-                fruits += 1 + *repeats;
-                droplets += slider_objects.len() - 1 - *repeats;
+                    if since_last_tick > 80.0 {
+                        let mut tiny = since_last_tick;
+
+                        while tiny > 100.0 {
+                            tiny /= 2.0;
+                        }
+
+                        let mut t = tiny;
+
+                        while t < since_last_tick {
+                            tiny_droplet_times.push(window[0].1 + t);
+                            t += tiny;
+                        }
+                    }
+                }
 
-                let iter = slider_objects.into_iter().map(CatchObject::new);
+                let iter = slider_objects
+                    .into_iter()
+                    .map(|(pos, time, is_fruit)| (CatchObject::new((pos, time)), is_fruit));
 
                 Some(Some(FruitOrJuice::Juice(iter)))
             }
@@ -184,6 +236,38 @@ pub fn stars(map: &Beatmap, mods: impl Mods) -> DifficultyAttributes {
         .filter_map(identity)
         .flatten();
 
+    // 2B maps can have a circle start in the middle of a slider; the scan above
+    // emits each slider's nested objects before the next circle, so the stream
+    // needs a legacy stable sort to recover true chronological order before
+    // hyper-dash init and strain processing see it.
+    let mut hit_objects: Vec<(CatchObject, bool)> = hit_objects.collect();
+    legacy_sort(&mut hit_objects);
+
+    // For a partial play, only the first `n` catch objects were actually seen;
+    // everything after that is simply discarded instead of being fed to `Movement`.
+    if let Some(n) = passed_objects {
+        hit_objects.truncate(n);
+    }
+
+    if hit_objects.len() < 2 {
+        return Ok(DifficultyAttributes::default());
+    }
+
+    let fruits = hit_objects.iter().filter(|(_, is_fruit)| *is_fruit).count();
+    let droplets = hit_objects.len() - fruits;
+
+    // A partial play never saw tiny droplets generated after the last passed
+    // catch object, so only count the ones up to that point.
+    let tiny_droplets = match passed_objects {
+        Some(_) => {
+            let cutoff = hit_objects.last().unwrap().0.time;
+            tiny_droplet_times.iter().filter(|&&t| t <= cutoff).count()
+        }
+        None => tiny_droplet_times.len(),
+    };
+
+    let mut hit_objects = hit_objects.into_iter().map(|(h, _)| h);
+
     // Hyper dash business
     let half_catcher_width = calculate_catch_width(attributes.cs) / 2.0 / ALLOWED_CATCH_RANGE;
     let mut last_direction = 0;
@@ -195,6 +279,10 @@ pub fn stars(map: &Beatmap, mods: impl Mods) -> DifficultyAttributes {
     let mut current_section_end =
         (map.hit_objects[0].start_time / section_len).ceil() * section_len;
 
+    // `(peak, section_end_time)` pairs on the same `SECTION_LENGTH`-spaced grid
+    // `movement` itself uses, surfaced for difficulty-over-time graphing.
+    let mut section_peaks = Vec::new();
+
     let mut prev = hit_objects.next().unwrap();
     let mut curr = hit_objects.next().unwrap();
 
@@ -221,7 +309,7 @@ pub fn stars(map: &Beatmap, mods: impl Mods) -> DifficultyAttributes {
         );
 
         while h.base.time > current_section_end {
-            movement.save_current_peak();
+            section_peaks.push((movement.save_current_peak(), current_section_end));
             movement.start_new_section_from(current_section_end);
             current_section_end += section_len;
         }
@@ -241,25 +329,59 @@ pub fn stars(map: &Beatmap, mods: impl Mods) -> DifficultyAttributes {
     );
 
     while h.base.time > current_section_end {
-        movement.save_current_peak();
+        section_peaks.push((movement.save_current_peak(), current_section_end));
         movement.start_new_section_from(current_section_end);
 
         current_section_end += section_len;
     }
 
     movement.process(&h);
-    movement.save_current_peak();
+    section_peaks.push((movement.save_current_peak(), current_section_end));
 
     let stars = movement.difficulty_value().sqrt() * STAR_SCALING_FACTOR;
 
-    DifficultyAttributes {
+    Ok(DifficultyAttributes {
         stars,
+        mode: map.mode,
         n_fruits: fruits,
         n_droplets: droplets,
+        n_tiny_droplets: tiny_droplets,
         max_combo: fruits + droplets,
+        section_peaks,
+    })
+}
+
+/// Error returned when calculating osu!catch difficulty for a beatmap whose
+/// [`GameMode`] is neither [`GameMode::Catch`] nor [`GameMode::Osu`] (the
+/// latter converts directly into the catch ruleset).
+#[derive(Debug)]
+pub enum CatchError {
+    WrongMode(GameMode),
+    #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+    Parse(parse::ParseError),
+}
+
+impl fmt::Display for CatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongMode(mode) => {
+                write!(f, "can not calculate osu!catch difficulty for a {:?} beatmap", mode)
+            }
+            #[cfg(any(feature = "async_tokio", feature = "async_std"))]
+            Self::Parse(why) => write!(f, "failed to parse beatmap: {}", why),
+        }
     }
 }
 
+impl std::error::Error for CatchError {}
+
+/// Stably sorts nested catch objects by time, mirroring osu!'s legacy sort so that
+/// objects with equal timestamps keep their original relative order.
+#[inline]
+fn legacy_sort(hit_objects: &mut [(CatchObject, bool)]) {
+    hit_objects.sort_by(|(a, _), (b, _)| a.time.partial_cmp(&b.time).unwrap_or(Ordering::Equal));
+}
+
 #[inline]
 pub(crate) fn calculate_catch_width(cs: f32) -> f32 {
     let scale = 1.0 - 0.7 * (cs - 5.0) / 5.0;
@@ -268,12 +390,12 @@ pub(crate) fn calculate_catch_width(cs: f32) -> f32 {
 }
 
 enum FruitOrJuice<I> {
-    Fruit(Option<CatchObject>),
+    Fruit(Option<(CatchObject, bool)>),
     Juice(I),
 }
 
-impl<I: Iterator<Item = CatchObject>> Iterator for FruitOrJuice<I> {
-    type Item = CatchObject;
+impl<I: Iterator<Item = (CatchObject, bool)>> Iterator for FruitOrJuice<I> {
+    type Item = (CatchObject, bool);
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
@@ -293,12 +415,31 @@ impl<I: Iterator<Item = CatchObject>> Iterator for FruitOrJuice<I> {
     }
 }
 
-#[derive(Default)]
 pub struct DifficultyAttributes {
     pub stars: f32,
+    pub mode: GameMode,
     pub max_combo: usize,
     pub n_fruits: usize,
     pub n_droplets: usize,
+    pub n_tiny_droplets: usize,
+    /// Ordered `(peak, section_end_time)` pairs, one per `SECTION_LENGTH`
+    /// section `movement` processed. Useful for rendering a difficulty-over-time
+    /// graph or finding the hardest section of the map.
+    pub section_peaks: Vec<(f32, f32)>,
+}
+
+impl Default for DifficultyAttributes {
+    fn default() -> Self {
+        Self {
+            stars: 0.0,
+            mode: GameMode::Catch,
+            max_combo: 0,
+            n_fruits: 0,
+            n_droplets: 0,
+            n_tiny_droplets: 0,
+            section_peaks: Vec::new(),
+        }
+    }
 }
 
 pub struct PpResult {
@@ -306,22 +447,32 @@ pub struct PpResult {
     pub stars: f32,
 }
 
+/// Entry point for pp calculation.
+///
+/// Returns [`CatchError::WrongMode`] if the map is neither an osu!catch map
+/// nor an osu!standard map (which converts directly into the catch ruleset),
+/// so callers get a clear error instead of [`PpCalculator`] silently
+/// producing meaningless numbers for e.g. a taiko or mania map.
 pub trait PpProvider {
-    fn pp(&self) -> PpCalculator;
+    fn pp(&self) -> Result<PpCalculator, CatchError>;
 }
 
 impl PpProvider for Beatmap {
-    fn pp(&self) -> PpCalculator {
-        PpCalculator::new(self)
+    fn pp(&self) -> Result<PpCalculator, CatchError> {
+        if self.mode != GameMode::Catch && self.mode != GameMode::Osu {
+            return Err(CatchError::WrongMode(self.mode));
+        }
+
+        Ok(PpCalculator::new(self))
     }
 }
 
-// TODO: Allow partial plays
 pub struct PpCalculator<'m> {
     map: &'m Beatmap,
     attributes: Option<DifficultyAttributes>,
     mods: u32,
     combo: Option<usize>,
+    passed_objects: Option<usize>,
 
     n_fruits: Option<usize>,
     n_droplets: Option<usize>,
@@ -337,6 +488,7 @@ impl<'m> PpCalculator<'m> {
             attributes: None,
             mods: 0,
             combo: None,
+            passed_objects: None,
 
             n_fruits: None,
             n_droplets: None,
@@ -352,6 +504,14 @@ impl<'m> PpCalculator<'m> {
         self
     }
 
+    /// Only consider the first `n` catch objects (fruits and droplets, not tiny
+    /// droplets), i.e. calculate pp for a failed or still in-progress play.
+    pub fn passed_objects(mut self, n: usize) -> Self {
+        self.passed_objects.replace(n);
+
+        self
+    }
+
     pub fn mods(mut self, mods: u32) -> Self {
         self.mods = mods;
 
@@ -397,9 +557,10 @@ impl<'m> PpCalculator<'m> {
     /// Generate the hit results with respect to the given accuracy between `0` and `100`.
     ///
     /// Be sure to set `misses` beforehand! Also, if available, set `attributes` beforehand.
-    pub fn accuracy(mut self, acc: f32) -> Self {
+    pub fn accuracy(mut self, acc: f32) -> Result<Self, CatchError> {
         if self.attributes.is_none() {
-            self.attributes.replace(stars(self.map, self.mods));
+            self.attributes
+                .replace(stars_partial(self.map, self.mods, self.passed_objects)?);
         }
 
         let attributes = self.attributes.as_ref().unwrap();
@@ -414,7 +575,7 @@ impl<'m> PpCalculator<'m> {
                 .saturating_sub(self.n_misses.saturating_sub(n_droplets))
         });
 
-        let max_tiny_droplets = 0; // TODO
+        let max_tiny_droplets = attributes.n_tiny_droplets;
 
         let n_tiny_droplets = self.n_tiny_droplets.unwrap_or_else(|| {
             ((acc * (attributes.max_combo + max_tiny_droplets) as f32).round() as usize)
@@ -422,21 +583,21 @@ impl<'m> PpCalculator<'m> {
                 .saturating_sub(n_droplets)
         });
 
-        let n_tiny_droplet_misses = max_tiny_droplets - n_tiny_droplets;
+        let n_tiny_droplet_misses = max_tiny_droplets.saturating_sub(n_tiny_droplets);
 
         self.n_fruits.replace(n_fruits);
         self.n_droplets.replace(n_droplets);
         self.n_tiny_droplets.replace(n_tiny_droplets);
         self.n_tiny_droplet_misses.replace(n_tiny_droplet_misses);
 
-        self
+        Ok(self)
     }
 
-    pub fn calculate(mut self) -> PpResult {
-        let attributes = self
-            .attributes
-            .take()
-            .unwrap_or_else(|| stars(self.map, self.mods));
+    pub fn calculate(mut self) -> Result<PpResult, CatchError> {
+        let attributes = match self.attributes.take() {
+            Some(attributes) => attributes,
+            None => stars_partial(self.map, self.mods, self.passed_objects)?,
+        };
 
         let stars = attributes.stars;
 
@@ -496,7 +657,7 @@ impl<'m> PpCalculator<'m> {
             pp *= 0.9;
         }
 
-        PpResult { pp, stars }
+        Ok(PpResult { pp, stars })
     }
 
     #[inline]
@@ -530,6 +691,36 @@ impl<'m> PpCalculator<'m> {
     }
 }
 
+/// Parses the beatmap at `path` without blocking the async runtime, then
+/// calculates its osu!catch star rating.
+///
+/// Requires the `async_tokio` or `async_std` feature.
+#[cfg(any(feature = "async_tokio", feature = "async_std"))]
+pub async fn stars_async(
+    path: impl AsRef<std::path::Path>,
+    mods: impl Mods,
+) -> Result<DifficultyAttributes, CatchError> {
+    let map = Beatmap::parse_async(path).await.map_err(CatchError::Parse)?;
+
+    stars_partial(&map, mods, None)
+}
+
+#[cfg(any(feature = "async_tokio", feature = "async_std"))]
+impl PpCalculator<'_> {
+    /// Reads and parses the beatmap at `path` asynchronously, computes its pp
+    /// calculator, and immediately runs [`PpCalculator::calculate`] on it.
+    ///
+    /// Requires the `async_tokio` or `async_std` feature.
+    pub async fn from_path_async(
+        path: impl AsRef<std::path::Path>,
+        mods: u32,
+    ) -> Result<PpResult, CatchError> {
+        let map = Beatmap::parse_async(path).await.map_err(CatchError::Parse)?;
+
+        map.pp()?.mods(mods).calculate()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -553,7 +744,7 @@ mod tests {
         };
 
         let mods = 0;
-        let stars = stars(&map, mods).stars;
+        let stars = stars(&map, mods).unwrap().stars;
 
         println!("Stars: {} [map={} | mods={}]", stars, map_id, mods);
     }
@@ -614,7 +805,7 @@ mod tests {
                 Err(why) => panic!("Error while parsing map {}: {}", map_id, why),
             };
 
-            let stars = stars(&map, mods).stars;
+            let stars = stars(&map, mods).unwrap().stars;
 
             assert!(
                 (stars - expected_stars).abs() < margin,
@@ -0,0 +1,105 @@
+use crate::difficulty_object::DifficultyObject;
+
+const ABSOLUTE_PLAYER_POSITIONING_ERROR: f32 = 16.0;
+const DIRECTION_CHANGE_BONUS: f32 = 21.0;
+
+const STRAIN_DECAY_BASE: f32 = 0.15;
+const DECAY_WEIGHT: f32 = 0.94;
+const SKILL_MULTIPLIER: f32 = 900.0;
+
+/// Tracks catcher movement strain across a map, the osu!catch equivalent of
+/// an osu!standard `Skill`.
+pub(crate) struct Movement {
+    pub(crate) half_catcher_width: f32,
+
+    current_strain: f32,
+    current_section_peak: f32,
+    strain_peaks: Vec<f32>,
+
+    last_player_pos: Option<f32>,
+    last_direction: i32,
+}
+
+impl Movement {
+    pub(crate) fn new(cs: f32) -> Self {
+        Self {
+            half_catcher_width: crate::calculate_catch_width(cs) / 2.0,
+
+            current_strain: 0.0,
+            current_section_peak: 0.0,
+            strain_peaks: Vec::new(),
+
+            last_player_pos: None,
+            last_direction: 0,
+        }
+    }
+
+    fn strain_decay(ms: f32) -> f32 {
+        STRAIN_DECAY_BASE.powf(ms / 1000.0)
+    }
+
+    fn strain_value_of(&mut self, current: &DifficultyObject) -> f32 {
+        let last_player_pos = self
+            .last_player_pos
+            .unwrap_or(current.base.position);
+
+        let player_pos = last_player_pos.clamp(
+            current.base.position - (self.half_catcher_width - ABSOLUTE_PLAYER_POSITIONING_ERROR),
+            current.base.position + (self.half_catcher_width - ABSOLUTE_PLAYER_POSITIONING_ERROR),
+        );
+
+        let dist_moved = player_pos - last_player_pos;
+        self.last_player_pos.replace(player_pos);
+
+        let direction = if dist_moved.abs() < f32::EPSILON {
+            0
+        } else if dist_moved > 0.0 {
+            1
+        } else {
+            -1
+        };
+
+        let mut value = (dist_moved.abs() / current.strain_time).powf(1.3);
+
+        if direction != 0 && direction == -self.last_direction {
+            value += DIRECTION_CHANGE_BONUS / current.strain_time;
+        }
+
+        self.last_direction = direction;
+
+        value * SKILL_MULTIPLIER
+    }
+
+    pub(crate) fn process(&mut self, current: &DifficultyObject) {
+        self.current_strain *= Self::strain_decay(current.strain_time);
+        self.current_strain += self.strain_value_of(current) * (1.0 - STRAIN_DECAY_BASE);
+
+        self.current_section_peak = self.current_strain.max(self.current_section_peak);
+    }
+
+    /// Starts a new `SECTION_LENGTH` section. Call after [`Self::save_current_peak`].
+    pub(crate) fn start_new_section_from(&mut self, _time: f32) {
+        self.current_section_peak = self.current_strain;
+    }
+
+    /// Saves the peak strain of the section that just ended, returning it so
+    /// callers can build a difficulty-over-time timeline out of it.
+    pub(crate) fn save_current_peak(&mut self) -> f32 {
+        self.strain_peaks.push(self.current_section_peak);
+
+        self.current_section_peak
+    }
+
+    /// Aggregates all saved section peaks into a single difficulty value,
+    /// weighting the hardest sections the most.
+    pub(crate) fn difficulty_value(&self) -> f32 {
+        let mut peaks = self.strain_peaks.clone();
+        peaks.sort_by(|a, b| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+
+        peaks
+            .into_iter()
+            .filter(|peak| *peak > 0.0)
+            .enumerate()
+            .fold(0.0, |total, (i, peak)| total + peak * DECAY_WEIGHT.powi(i as i32))
+    }
+}